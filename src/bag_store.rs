@@ -1,4 +1,4 @@
-use std::{collections::{BTreeMap, btree_map::{Entry, self}}, borrow::Borrow, ops::RangeBounds};
+use std::{collections::{BTreeMap, BTreeSet, btree_map::{Entry, self}}, borrow::Borrow, ops::RangeBounds};
 
 #[derive(Clone)]
 pub enum BagStoreEvent<K, T, M> {
@@ -71,6 +71,34 @@ impl<'a, K, T> Iterator for RangeIter<'a, K, T> {
     }
 }
 
+pub struct IntoIter<K, T> {
+    iter: btree_map::IntoIter<K, Vec<T>>,
+    key: Option<K>,
+    sub_iter: std::vec::IntoIter<T>,
+}
+
+impl<K, T> Iterator for IntoIter<K, T> where K: Clone {
+    type Item = (K, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.sub_iter.next() {
+            None => {
+                match self.iter.next() {
+                    None => None,
+                    Some((k, vec)) => {
+                        self.key = Some(k);
+                        self.sub_iter = vec.into_iter();
+                        self.next()
+                    },
+                }
+            },
+            Some(e) => {
+                Some((self.key.clone().unwrap(), e))
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BagStore<K, T, M> {
     store: BTreeMap<K, Vec<T>>,
@@ -89,6 +117,7 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
         }
     }
     
+    #[allow(clippy::needless_lifetimes)]
     pub fn get<'a>(&'a self, key: K) -> &'a Vec<T> {
         self.store.get(&key).unwrap_or(&self.empty)
     }
@@ -114,6 +143,25 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
         None
     }
 
+    pub fn pop_last(&mut self) -> Option<(K, Vec<T>)> where K: Clone {
+        let key = self.store.iter().next_back().map(|e| e.0.clone());
+
+        if let Some(k) = key {
+            let ret = self.store.remove_entry(&k);
+            if let Some((k, v)) = ret {
+                self.fire_event(|| BagStoreEvent::RemovedVec(v.clone()));
+                self.count -= v.len();
+                return Some((k, v));
+            }
+        }
+
+        None
+    }
+
+    pub fn peek_first(&self) -> Option<(&K, &Vec<T>)> {
+        self.store.iter().next()
+    }
+
     pub fn peek_last(&self) -> Option<(&K, &Vec<T>)> {
         self.store.iter().last()
     }
@@ -125,7 +173,7 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
     
     // Does not notify observers.
     fn add_internal(&mut self, key: K, e: T) where K: Clone, T: Clone {
-        self.store.entry(key.clone()).or_insert(Vec::new()).push(e.clone());
+        self.store.entry(key.clone()).or_default().push(e.clone());
         self.count += 1;
     }
     
@@ -159,12 +207,13 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
         ret
     }
     
+    #[allow(clippy::ptr_arg)]
     pub fn remove_vec(&mut self, key: &K, value_table: &Vec<T>) where K: Clone, T: Clone {
         let removed: Vec<T> = self.remove_vec_internal(key, value_table);
         self.fire_event(|| BagStoreEvent::RemovedVec(removed));
     }
     
-    fn remove_vec_internal(&mut self, key: &K, value_table: &Vec<T>) -> Vec<T> where K: Clone, T: Clone {
+    fn remove_vec_internal(&mut self, key: &K, value_table: &[T]) -> Vec<T> where K: Clone, T: Clone {
         let mut removed: Vec<T> = vec![];
         if let Some(cur) = self.store.get_mut(key) {
             for e in value_table.iter() {
@@ -180,7 +229,7 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
     // Does not notify observers
     fn remove_internal(&mut self, key: &K, e: &T) -> Option<T> where T: Clone {
         let mut entry_becomes_empty = false;
-        let ret = self.store.get_mut(&key).and_then(|vec| {
+        let ret = self.store.get_mut(key).and_then(|vec| {
             vec.iter().position(|o| *o == *e).map(|idx| {
                 let e = vec.remove(idx);
                 if vec.is_empty() {
@@ -191,7 +240,7 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
         });
         if ret.is_some() {
             if entry_becomes_empty {
-                self.store.remove(&key);
+                self.store.remove(key);
             }
             self.count -= 1;
         }
@@ -221,6 +270,8 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
         }
     }
 
+    // Does not fire Changed when none of the 'from' entries exist.
+    #[allow(clippy::type_complexity)]
     pub fn change(&mut self, from_to: &[((K, T), (K, T))], metadata: M) where T: Clone, K: Clone {
         let mut result: Vec<((K, T), (K, T))> = Vec::with_capacity(from_to.len());
 
@@ -237,16 +288,36 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
             self.add_internal(k.clone(), v.clone());
         }
 
-        self.fire_event(|| BagStoreEvent::Changed { from_to: result, removed: vec![], metadata });
+        if !result.is_empty() {
+            self.fire_event(|| BagStoreEvent::Changed { from_to: result, removed: vec![], metadata });
+        }
     }
 
+    // Move the first element equal to the value from a key to another. Returns false if not found.
+    pub fn move_value(&mut self, from: &K, value: &T, to: K, metadata: M) -> bool where K: Clone {
+        match self.remove_internal(from, value) {
+            None => false,
+            Some(v) => {
+                self.add_internal(to.clone(), v.clone());
+                self.fire_event(|| BagStoreEvent::Changed {
+                    from_to: vec![((from.clone(), v.clone()), (to, v))], removed: vec![], metadata
+                });
+                true
+            }
+        }
+    }
+
+    // Does not fire BulkAddedRemoved when models is empty.
     pub fn bulk_add(&mut self, models: Vec<(K, T)>, metadata: M) where K: Clone, T: Clone {
         for (key, value) in models.iter() {
             self.add_internal(key.clone(), value.clone());
         }
-        self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: models, removed: vec![], metadata });
+        if !models.is_empty() {
+            self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: models, removed: vec![], metadata });
+        }
     }
 
+    // Does not fire BulkAddedRemoved when none of the entries exist.
     pub fn bulk_remove(&mut self, models: &[(K, T)], metadata: M) -> Vec<(K, T)> where K: Clone, T: Clone {
         let mut removed: Vec<(K, T)> = Vec::with_capacity(models.len());
 
@@ -256,10 +327,13 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
             }
         }
 
-        self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        if !removed.is_empty() {
+            self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        }
         removed
     }
 
+    // Does not fire BulkAddedRemoved when nothing is removed.
     pub fn retain_values<F>(&mut self, metadata: M, f: F) -> Vec<(K, T)>
       where F: Fn(&T) -> bool, K: Clone
     {
@@ -278,10 +352,66 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
             ! v.is_empty()
         });
 
-        self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        if !removed.is_empty() {
+            self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        }
         removed
     }
 
+    // True if the key has no elements. Buckets emptied by remove_vec() are also treated as empty.
+    pub fn is_key_empty(&self, key: &K) -> bool {
+        self.len_for(key) == 0
+    }
+
+    pub fn len_for(&self, key: &K) -> usize {
+        self.store.get(key).map(|vec| vec.len()).unwrap_or(0)
+    }
+
+    pub fn count_of(&self, key: &K, value: &T) -> usize {
+        self.store.get(key).map(|vec| vec.iter().filter(|e| *e == value).count()).unwrap_or(0)
+    }
+
+    // Remove duplicated values within each key keeping the first occurrence.
+    pub fn dedup(&mut self, metadata: M) -> Vec<(K, T)> where K: Clone {
+        let mut removed: Vec<(K, T)> = vec![];
+
+        for (k, v) in self.store.iter_mut() {
+            let mut i = 1;
+            while i < v.len() {
+                if v[..i].contains(&v[i]) {
+                    removed.push((k.clone(), v.remove(i)));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.count -= removed.len();
+
+        if !removed.is_empty() {
+            self.fire_event(|| BagStoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        }
+        removed
+    }
+
+    // Events to transform this store into the target. One BulkAddedRemoved is emitted per differing key in key order.
+    pub fn diff_to_events(&self, target: &BagStore<K, T, M>, metadata: M) -> Vec<BagStoreEvent<K, T, M>> where M: Clone, K: Clone {
+        let keys: BTreeSet<&K> = self.store.keys().chain(target.store.keys()).collect();
+        let mut events = vec![];
+
+        for k in keys {
+            let from = self.store.get(k).unwrap_or(&self.empty);
+            let to = target.store.get(k).unwrap_or(&target.empty);
+            let removed: Vec<(K, T)> = multiset_diff(from, to).into_iter().map(|e| (k.clone(), e)).collect();
+            let added: Vec<(K, T)> = multiset_diff(to, from).into_iter().map(|e| (k.clone(), e)).collect();
+
+            if !added.is_empty() || !removed.is_empty() {
+                events.push(BagStoreEvent::BulkAddedRemoved { added, removed, metadata: metadata.clone() });
+            }
+        }
+
+        events
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.count
@@ -316,6 +446,51 @@ impl<K, T, M> BagStore<K, T, M> where K:Ord + 'static, T: PartialEq + Clone + 's
     }
 }
 
+// Elements in 'a' but not in 'b' counting multiplicity.
+fn multiset_diff<T: PartialEq + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut rest: Vec<&T> = b.iter().collect();
+    let mut diff = vec![];
+
+    for e in a.iter() {
+        match rest.iter().position(|r| *r == e) {
+            Some(idx) => { rest.swap_remove(idx); }
+            None => diff.push(e.clone()),
+        }
+    }
+    diff
+}
+
+impl<K, T, M> IntoIterator for BagStore<K, T, M> where K: Clone {
+    type Item = (K, T);
+    type IntoIter = IntoIter<K, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            iter: self.store.into_iter(),
+            key: None,
+            sub_iter: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<K, T> FromIterator<(K, T)> for BagStore<K, T, ()> where K: Ord + Clone + 'static, T: PartialEq + Clone + 'static {
+    // The resulting store does not hold events.
+    fn from_iter<I: IntoIterator<Item = (K, T)>>(iter: I) -> Self {
+        let mut store = Self::new(false);
+        for (key, value) in iter {
+            store.add_internal(key, value);
+        }
+        store
+    }
+}
+
+// Fires BulkAddedRemoved with the default metadata.
+impl<K, T, M> Extend<(K, T)> for BagStore<K, T, M> where K: Ord + Clone + 'static, T: PartialEq + Clone + 'static, M: Default {
+    fn extend<I: IntoIterator<Item = (K, T)>>(&mut self, iter: I) {
+        self.bulk_add(iter.into_iter().collect(), M::default());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::btree_map::Range;
@@ -499,7 +674,7 @@ mod tests {
         store.add(0.0.into(), "Hello", 0);
         store.add(0.0.into(), "World", 0);
         
-        store.change(&vec![((0.0.into(), "Hello"), (1.0.into(), "Foo"))], 123);
+        store.change(&[((0.0.into(), "Hello"), (1.0.into(), "Foo"))], 123);
         
         let vec0 = store.get(0.0.into());
         assert_eq!(vec0.len(), 1);
@@ -631,4 +806,266 @@ mod tests {
             _ => panic!("Logic error."),
         }
     }
+
+    #[test]
+    fn from_iter() {
+        let store: BagStore<i32, &str, ()> = vec![(1, "a"), (0, "b"), (1, "c"), (2, "d"), (0, "e")].into_iter().collect();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(0), &vec!["b", "e"]);
+        assert_eq!(store.get(1), &vec!["a", "c"]);
+        assert_eq!(store.get(2), &vec!["d"]);
+
+        let mut z = store.iter();
+        assert_eq!(z.next(), Some((&0, &"b")));
+        assert_eq!(z.next(), Some((&0, &"e")));
+        assert_eq!(z.next(), Some((&1, &"a")));
+        assert_eq!(z.next(), Some((&1, &"c")));
+        assert_eq!(z.next(), Some((&2, &"d")));
+        assert_eq!(z.next(), None);
+    }
+
+    #[test]
+    fn extend() {
+        let mut store: BagStore<i32, &str, ()> = BagStore::new(true);
+        store.add(0, "a", ());
+        store.clear_events();
+
+        store.extend(vec![(0, "b"), (1, "c")]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(0), &vec!["a", "b"]);
+        assert_eq!(store.get(1), &vec!["c"]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BagStoreEvent::BulkAddedRemoved { added, removed, .. } => {
+                assert_eq!(added, &vec![(0, "b"), (1, "c")]);
+                assert_eq!(removed.len(), 0);
+            }
+            _ => panic!("Logic error."),
+        }
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut store: BagStore<i32, String, i32> = BagStore::new(true);
+        store.add(1, "Foo".to_owned(), 0);
+        store.add(0, "Hello".to_owned(), 0);
+        store.add(1, "Bar".to_owned(), 0);
+        store.add(0, "World".to_owned(), 0);
+
+        let mut z = store.clone().into_iter();
+        assert_eq!(z.next(), Some((0, "Hello".to_owned())));
+        assert_eq!(z.next(), Some((0, "World".to_owned())));
+        assert_eq!(z.next(), Some((1, "Foo".to_owned())));
+        assert_eq!(z.next(), Some((1, "Bar".to_owned())));
+        assert_eq!(z.next(), None);
+
+        let mut count = 0;
+        for (k, v) in store {
+            assert!(k == 0 || k == 1);
+            assert!(!v.is_empty());
+            count += 1;
+        }
+        assert_eq!(count, 4);
+
+        let bag: BagStore<i32, &str, ()> = vec![(1, "b"), (0, "a")].into_iter().collect();
+        assert_eq!(bag.into_iter().collect::<Vec<_>>(), vec![(0, "a"), (1, "b")]);
+    }
+
+    #[test]
+    fn pop_last() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(true);
+        assert_eq!(store.pop_last(), None);
+        assert_eq!(store.peek_first(), None);
+
+        store.add(0, "Hello", 0);
+        store.add(1, "Foo", 0);
+        store.add(1, "Bar", 0);
+        assert_eq!(store.peek_first(), Some((&0, &vec!["Hello"])));
+        store.clear_events();
+
+        assert_eq!(store.pop_last(), Some((1, vec!["Foo", "Bar"])));
+        assert_eq!(store.len(), 1);
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BagStoreEvent::RemovedVec(removed) => assert_eq!(removed, &vec!["Foo", "Bar"]),
+            _ => panic!("Logic error."),
+        }
+
+        assert_eq!(store.pop_last(), Some((0, vec!["Hello"])));
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.pop_last(), None);
+    }
+
+    #[test]
+    fn count_of() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(false);
+        store.add(0, "a", 0);
+        store.add(0, "b", 0);
+        store.add(0, "a", 0);
+        store.add(1, "a", 0);
+
+        assert_eq!(store.count_of(&0, &"a"), 2);
+        assert_eq!(store.count_of(&0, &"b"), 1);
+        assert_eq!(store.count_of(&0, &"c"), 0);
+        assert_eq!(store.count_of(&1, &"a"), 1);
+        assert_eq!(store.count_of(&2, &"a"), 0);
+    }
+
+    #[test]
+    fn dedup() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(true);
+        store.add(0, "a", 0);
+        store.add(0, "b", 0);
+        store.add(0, "a", 0);
+        store.add(0, "b", 0);
+        store.add(0, "a", 0);
+        store.add(1, "c", 0);
+        store.add(2, "d", 0);
+        store.add(2, "d", 0);
+        store.clear_events();
+
+        let removed = store.dedup(123);
+        assert_eq!(removed, vec![(0, "a"), (0, "b"), (0, "a"), (2, "d")]);
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.get(0), &vec!["a", "b"]);
+        assert_eq!(store.get(1), &vec!["c"]);
+        assert_eq!(store.get(2), &vec!["d"]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BagStoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                assert_eq!(added.len(), 0);
+                assert_eq!(removed, &vec![(0, "a"), (0, "b"), (0, "a"), (2, "d")]);
+                assert_eq!(metadata, &123);
+            }
+            _ => panic!("Logic error."),
+        }
+    }
+
+    #[test]
+    fn diff_to_events() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(false);
+        store.add(0, "a", 0);
+        store.add(0, "b", 0);
+        store.add(1, "c", 0);
+        store.add(1, "c", 0);
+
+        let mut target = store.clone();
+        target.remove(&1, &"c");
+        target.add(0, "d", 0);
+        assert!(store.diff_to_events(&store.clone(), 0).is_empty());
+
+        let events = store.diff_to_events(&target, 123);
+        assert_eq!(events.len(), 2);
+        for e in events {
+            match e {
+                BagStoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                    assert_eq!(metadata, 123);
+                    store.bulk_remove(&removed, metadata);
+                    store.bulk_add(added, metadata);
+                }
+                _ => panic!("Logic error."),
+            }
+        }
+
+        assert_eq!(store.len(), target.len());
+        assert_eq!(store.iter().collect::<Vec<_>>(), target.iter().collect::<Vec<_>>());
+        assert_eq!(store.get(0), &vec!["a", "b", "d"]);
+        assert_eq!(store.get(1), &vec!["c"]);
+    }
+
+    #[test]
+    fn move_value() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(true);
+        store.add(0, "Hello", 0);
+        store.add(0, "World", 0);
+        store.add(1, "Foo", 0);
+        store.clear_events();
+
+        assert!(store.move_value(&0, &"Hello", 1, 123));
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(0), &vec!["World"]);
+        assert_eq!(store.get(1), &vec!["Foo", "Hello"]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BagStoreEvent::Changed { from_to, removed, metadata } => {
+                assert_eq!(from_to, &vec![((0, "Hello"), (1, "Hello"))]);
+                assert_eq!(removed.len(), 0);
+                assert_eq!(*metadata, 123);
+            }
+            _ => panic!("Logic error."),
+        }
+        store.clear_events();
+
+        assert!(!store.move_value(&0, &"Hello", 2, 123));
+        assert!(!store.move_value(&5, &"Hello", 2, 123));
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.events().len(), 0);
+
+        assert!(store.move_value(&0, &"World", 2, 123));
+        assert!(store.get(0).is_empty());
+        assert_eq!(store.iter_vec().count(), 2);
+    }
+
+    #[test]
+    fn len_for() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(false);
+        assert!(store.is_key_empty(&0));
+        assert_eq!(store.len_for(&0), 0);
+
+        store.add(0, "Hello", 0);
+        store.add(0, "World", 0);
+        store.add(1, "Foo", 0);
+        assert!(!store.is_key_empty(&0));
+        assert_eq!(store.len_for(&0), 2);
+        assert!(!store.is_key_empty(&1));
+        assert_eq!(store.len_for(&1), 1);
+        assert!(store.is_key_empty(&2));
+        assert_eq!(store.len_for(&2), 0);
+
+        store.remove_vec(&1, &vec!["Foo"]);
+        assert!(store.is_key_empty(&1));
+        assert_eq!(store.len_for(&1), 0);
+    }
+
+    #[test]
+    fn retain_without_removal_fires_no_event() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(true);
+        store.add(0, "0", 0);
+        store.add(0, "00", 0);
+        store.clear_events();
+
+        assert_eq!(store.retain_values(123, |_| true), vec![]);
+        assert_eq!(store.dedup(123), vec![]);
+        assert_eq!(store.bulk_remove(&[(1, "1")], 123), vec![]);
+        store.bulk_add(vec![], 123);
+        store.change(&[((1, "1"), (2, "2"))], 123);
+        assert_eq!(store.events().len(), 0);
+
+        assert_eq!(store.retain_values(123, |v| v.len() == 1), vec![(0, "00")]);
+        assert_eq!(store.events().len(), 1);
+    }
+
+    #[test]
+    fn extend_with_default_metadata() {
+        let mut store: BagStore<i32, &str, i32> = BagStore::new(true);
+        store.extend(vec![(0, "a"), (0, "b")]);
+        assert_eq!(store.get(0), &vec!["a", "b"]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            BagStoreEvent::BulkAddedRemoved { added, metadata, .. } => {
+                assert_eq!(added, &vec![(0, "a"), (0, "b")]);
+                assert_eq!(*metadata, 0);
+            }
+            _ => panic!("Logic error."),
+        }
+    }
 }
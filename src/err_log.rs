@@ -1,13 +1,13 @@
-use std::{time::SystemTime, collections::{VecDeque, vec_deque}};
+use std::{time::SystemTime, collections::{VecDeque, vec_deque}, sync::{Arc, Weak, atomic::{AtomicUsize, Ordering}}};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub enum Severity {
     Info,
     Warn,
     Err,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Entry {
     pub time: SystemTime,
     pub severity: Severity,
@@ -34,50 +34,158 @@ impl Entry {
             text: text.into(),
         }
     }
+
+    // Compare contents ignoring time.
+    pub fn matches(&self, severity: Severity, text: &str) -> bool {
+        self.severity == severity && self.text == text
+    }
+
+    pub fn eq_ignoring_time(&self, other: &Entry) -> bool {
+        self.severity == other.severity && self.text == other.text
+    }
+}
+
+pub trait LogObserver: Send + Sync {
+    fn on_log(&self, entry: &Entry);
+}
+
+/// Observer counting entries by severity.
+#[derive(Default)]
+pub struct CounterObserver {
+    info: AtomicUsize,
+    warn: AtomicUsize,
+    err: AtomicUsize,
+}
+
+impl CounterObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn info(&self) -> usize {
+        self.info.load(Ordering::Relaxed)
+    }
+
+    pub fn warn(&self) -> usize {
+        self.warn.load(Ordering::Relaxed)
+    }
+
+    pub fn err(&self) -> usize {
+        self.err.load(Ordering::Relaxed)
+    }
+}
+
+impl LogObserver for CounterObserver {
+    fn on_log(&self, entry: &Entry) {
+        let counter = match entry.severity {
+            Severity::Info => &self.info,
+            Severity::Warn => &self.warn,
+            Severity::Err => &self.err,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Which entry is evicted when the buffer is full.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EvictionPolicy {
+    /// The oldest entry.
+    Fifo,
+    /// The oldest entry of the lowest severity in the buffer, so that errors live longer than info.
+    /// Entries stay in time order. The newly logged entry is dropped instead if its severity is lower than
+    /// that of every buffered entry. Observers are notified of it either way.
+    PreferLowSeverity,
 }
 
 pub struct Logs {
     size: usize,
     logs: VecDeque<Entry>,
+    observers: Vec<Weak<dyn LogObserver>>,
+    policy: EvictionPolicy,
 }
 
 impl Logs {
     pub fn new(size: usize) -> Self {
+        Self::with_policy(size, EvictionPolicy::Fifo)
+    }
+
+    pub fn with_policy(size: usize, policy: EvictionPolicy) -> Self {
         Self {
             size,
             logs: VecDeque::with_capacity(size),
+            observers: vec![],
+            policy,
+        }
+    }
+
+    /// Observers are held weakly. Once dropped, they are no longer notified.
+    pub fn add_observer<O: LogObserver + 'static>(&mut self, observer: &Arc<O>) {
+        let weak: Weak<O> = Arc::downgrade(observer);
+        self.observers.push(weak);
+    }
+
+    fn push(&mut self, entry: Entry) {
+        let keep = self.trim(&entry);
+        self.observers.retain(|o| match o.upgrade() {
+            Some(o) => {
+                o.on_log(&entry);
+                true
+            }
+            None => false,
+        });
+        if keep {
+            self.logs.push_back(entry);
         }
     }
 
+    /// Makes room for the incoming entry. Returns false if the incoming entry should be dropped instead.
     #[inline]
-    fn trim(&mut self) {
+    fn trim(&mut self, incoming: &Entry) -> bool {
         if self.size <= self.logs.len() {
-            self.logs.remove(0);
+            let idx = match self.policy {
+                EvictionPolicy::Fifo => 0,
+                EvictionPolicy::PreferLowSeverity => {
+                    let lowest = self.logs.iter().map(|e| &e.severity).min();
+                    if lowest.map_or(false, |lowest| incoming.severity < *lowest) {
+                        return false;
+                    }
+                    self.logs.iter().position(|e| Some(&e.severity) == lowest).unwrap_or(0)
+                }
+            };
+            self.logs.remove(idx);
         }
+        true
     }
 
     pub fn info<S: Into<String>>(&mut self, text: S) {
         let text: String = text.into();
-        self.trim();
-        self.logs.push_back(Entry::info(text));
+        self.push(Entry::info(text));
     }
 
     pub fn warn<S: Into<String>>(&mut self, text: S) {
         let text: String = text.into();
-        self.trim();
-        self.logs.push_back(Entry::warn(text));
+        self.push(Entry::warn(text));
     }
 
     pub fn err<S: Into<String>>(&mut self, text: S) {
         let text: String = text.into();
-        self.trim();
-        self.logs.push_back(Entry::err(text));
+        self.push(Entry::err(text));
     }
 
     /// Oldest first order. You can call rev() to reverse the order.
     pub fn logs(&self) -> vec_deque::Iter<'_, Entry> {
         self.logs.iter()
     }
+
+    /// Oldest first order.
+    pub fn to_vec(&self) -> Vec<Entry> {
+        self.logs.iter().cloned().collect()
+    }
+
+    /// Entries logged at or after the specified time in oldest first order.
+    pub fn snapshot_since(&self, t: SystemTime) -> Vec<Entry> {
+        self.logs.iter().filter(|e| t <= e.time).cloned().collect()
+    }
 }
 
 #[macro_export]
@@ -100,7 +208,8 @@ macro_rules! err {
 
 #[cfg(test)]
 mod tests {
-    use super::Logs;
+    use std::{sync::Arc, time::{Duration, SystemTime}};
+    use super::{CounterObserver, Entry, EvictionPolicy, Logs, Severity};
 
     #[test]
     fn empty() {
@@ -123,4 +232,114 @@ mod tests {
         assert_eq!(riter.next().map(|e| e.text.clone()), Some("Hello".to_owned()));
         assert_eq!(riter.next(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn eq_ignoring_time() {
+        let now = SystemTime::now();
+        let e0 = Entry::new(now, Severity::Warn, "Hello");
+        let e1 = Entry::new(now + Duration::from_secs(1), Severity::Warn, "Hello");
+        assert_ne!(e0, e1);
+        assert!(e0.eq_ignoring_time(&e1));
+        assert!(!e0.eq_ignoring_time(&Entry::new(now, Severity::Err, "Hello")));
+        assert!(!e0.eq_ignoring_time(&Entry::new(now, Severity::Warn, "World")));
+
+        assert!(e1.matches(Severity::Warn, "Hello"));
+        assert!(!e1.matches(Severity::Info, "Hello"));
+        assert!(!e1.matches(Severity::Warn, "World"));
+    }
+
+    #[test]
+    fn to_vec() {
+        let mut logs = Logs::new(5);
+        assert!(logs.to_vec().is_empty());
+
+        info!(logs, "Hello");
+        warn!(logs, "World");
+        let snapshot = logs.to_vec();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.iter().eq(logs.logs()));
+        assert_eq!(logs.logs().count(), 2);
+    }
+
+    #[test]
+    fn snapshot_since() {
+        let now = SystemTime::now();
+        let mut logs = Logs::new(5);
+        logs.logs.push_back(Entry::new(now - Duration::from_secs(2), Severity::Info, "0"));
+        logs.logs.push_back(Entry::new(now - Duration::from_secs(1), Severity::Warn, "1"));
+        logs.logs.push_back(Entry::new(now, Severity::Err, "2"));
+
+        let snapshot = logs.snapshot_since(now - Duration::from_secs(1));
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot[0].matches(Severity::Warn, "1"));
+        assert!(snapshot[1].matches(Severity::Err, "2"));
+        assert_eq!(logs.snapshot_since(now + Duration::from_secs(1)).len(), 0);
+        assert_eq!(logs.snapshot_since(now - Duration::from_secs(3)).len(), 3);
+        assert_eq!(logs.logs().count(), 3);
+    }
+
+    #[test]
+    fn counter_observer() {
+        let mut logs = Logs::new(2);
+        let counter = Arc::new(CounterObserver::new());
+        logs.add_observer(&counter);
+
+        info!(logs, "0");
+        warn!(logs, "1");
+        err!(logs, "2");
+        info!(logs, "3");
+        assert_eq!(counter.info(), 2);
+        assert_eq!(counter.warn(), 1);
+        assert_eq!(counter.err(), 1);
+        assert_eq!(logs.observers.len(), 1);
+
+        drop(counter);
+        info!(logs, "4");
+        assert_eq!(logs.observers.len(), 0);
+    }
+
+    #[test]
+    fn logs_is_send_and_sync() {
+        fn is_send_sync<S: Send + Sync>(_: &S) {}
+
+        let mut logs = Logs::new(2);
+        logs.add_observer(&Arc::new(CounterObserver::new()));
+        is_send_sync(&logs);
+    }
+
+    #[test]
+    fn prefer_low_severity() {
+        let mut logs = Logs::with_policy(4, EvictionPolicy::PreferLowSeverity);
+        info!(logs, "i0");
+        info!(logs, "i1");
+        err!(logs, "e0");
+        info!(logs, "i2");
+        warn!(logs, "w0");
+        err!(logs, "e1");
+        let texts: Vec<&str> = logs.logs().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["e0", "i2", "w0", "e1"]);
+
+        err!(logs, "e2");
+        err!(logs, "e3");
+        let texts: Vec<&str> = logs.logs().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["e0", "e1", "e2", "e3"]);
+
+        // Entries less severe than every buffered one are dropped instead of errors.
+        info!(logs, "i3");
+        warn!(logs, "w1");
+        let texts: Vec<&str> = logs.logs().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["e0", "e1", "e2", "e3"]);
+
+        // The oldest error is evicted by a new error.
+        err!(logs, "e4");
+        let texts: Vec<&str> = logs.logs().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["e1", "e2", "e3", "e4"]);
+
+        let mut logs = Logs::new(2);
+        err!(logs, "e0");
+        info!(logs, "i0");
+        info!(logs, "i1");
+        let texts: Vec<&str> = logs.logs().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, vec!["i0", "i1"]);
+    }
+}
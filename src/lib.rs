@@ -1,3 +1,4 @@
+
 pub mod store;
 pub mod bag_store;
 pub mod nan_free_f32;
@@ -77,7 +78,7 @@ mod tests {
 
   #[test]
   fn many() {
-    let tbl = vec!["One", "Two", "Three"];
+    let tbl = ["One", "Two", "Three"];
     let mut z = tbl.iter();
     let mut s = sliding(&mut z);
     assert_eq!(s.next(), Some((&"One", &"Two")));
@@ -5,6 +5,7 @@ pub struct NanFreeF32(f32);
 
 pub const ZERO: NanFreeF32 = NanFreeF32(0.0);
 pub const MAX: NanFreeF32 = NanFreeF32(f32::MAX);
+const SIGN_BIT: u32 = 0x8000_0000;
 
 impl Eq for NanFreeF32 {
 }
@@ -13,6 +14,35 @@ impl NanFreeF32 {
     pub fn to_f32(&self) -> f32 {
         self.0
     }
+
+    // Bit exact comparison. Unlike == and Ord, -0.0 and +0.0 are distinguished.
+    pub fn bitwise_eq(&self, other: &NanFreeF32) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+
+    // Reduce into [0, period) with floored modulo. Panics if period is not positive or either value is infinite.
+    pub fn wrap(&self, period: NanFreeF32) -> NanFreeF32 {
+        assert!(
+            0.0 < period.0 && period.0.is_finite() && self.0.is_finite(),
+            "Period should be positive and finite, and the value should be finite."
+        );
+        let r = self.0.rem_euclid(period.0);
+        // Rounding can yield the period itself for tiny negative values.
+        Self::from(if r < period.0 { r } else { 0.0 })
+    }
+
+    // Unsigned integer whose order matches the order of floats. Useful for radix sorting.
+    // Unlike Ord, the key distinguishes zeros: -0.0 sorts before +0.0.
+    pub fn to_sortable_bits(&self) -> u32 {
+        let bits = self.0.to_bits();
+        if bits & SIGN_BIT != 0 { !bits } else { bits | SIGN_BIT }
+    }
+
+    // Inverse of to_sortable_bits(). Panics if the bits represent NaN.
+    pub fn from_sortable_bits(bits: u32) -> Self {
+        let bits = if bits & SIGN_BIT != 0 { bits & !SIGN_BIT } else { !bits };
+        Self::from(f32::from_bits(bits))
+    }
 }
 
 pub fn max(f0: NanFreeF32, f1: NanFreeF32) -> NanFreeF32 {
@@ -20,6 +50,7 @@ pub fn max(f0: NanFreeF32, f1: NanFreeF32) -> NanFreeF32 {
     else { f0 }
 }
 
+#[allow(clippy::derive_ord_xor_partial_ord)]
 impl Ord for NanFreeF32 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         if self.0 < other.0 {
@@ -79,4 +110,68 @@ mod tests {
     fn sub() {
         assert_eq!(NanFreeF32::from(1.1) - NanFreeF32::from(0.1), NanFreeF32::from(1.1 - 0.1));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sortable_bits() {
+        // In ascending order.
+        let values: Vec<NanFreeF32> = [
+            f32::NEG_INFINITY, f32::MIN, -1e10, -1.0, -2.5e-40, -0.0, 0.0, 2.5e-40, 1.0, 3.5, f32::MAX, f32::INFINITY,
+        ].iter().map(|f| NanFreeF32::from(*f)).collect();
+
+        let bits: Vec<u32> = values.iter().map(|f| f.to_sortable_bits()).collect();
+        assert!(bits.windows(2).all(|w| w[0] < w[1]), "{:?}", bits);
+        assert!(NanFreeF32::from(-0.0).to_sortable_bits() < NanFreeF32::from(0.0).to_sortable_bits());
+
+        for f in values.iter() {
+            assert_eq!(NanFreeF32::from_sortable_bits(f.to_sortable_bits()).to_f32().to_bits(), f.to_f32().to_bits());
+        }
+    }
+
+    #[test]
+    fn wrap() {
+        let one = NanFreeF32::from(1.0);
+        assert_eq!(NanFreeF32::from(0.25).wrap(one), NanFreeF32::from(0.25));
+        assert_eq!(NanFreeF32::from(2.25).wrap(one), NanFreeF32::from(0.25));
+        assert_eq!(NanFreeF32::from(-0.25).wrap(one), NanFreeF32::from(0.75));
+        assert_eq!(NanFreeF32::from(-1.75).wrap(one), NanFreeF32::from(0.25));
+        assert_eq!(NanFreeF32::from(1.0).wrap(one), NanFreeF32::from(0.0));
+        assert_eq!(NanFreeF32::from(-1.0).wrap(one), NanFreeF32::from(0.0));
+        assert_eq!(NanFreeF32::from(0.0).wrap(one), NanFreeF32::from(0.0));
+        assert!(NanFreeF32::from(-1e-10).wrap(one) < one);
+
+        let two_pi = NanFreeF32::from(std::f32::consts::TAU);
+        assert_eq!(two_pi.wrap(two_pi), NanFreeF32::from(0.0));
+        assert_eq!(NanFreeF32::from(-std::f32::consts::PI).wrap(two_pi), NanFreeF32::from(std::f32::consts::PI));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrap_should_reject_non_positive_period() {
+        let _ = NanFreeF32::from(1.0).wrap(NanFreeF32::from(0.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrap_should_reject_infinite_value() {
+        let _ = NanFreeF32::from(f32::INFINITY).wrap(NanFreeF32::from(1.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrap_should_reject_infinite_period() {
+        let _ = NanFreeF32::from(1.0).wrap(NanFreeF32::from(f32::INFINITY));
+    }
+
+    #[test]
+    fn bitwise_eq() {
+        let plus_zero = NanFreeF32::from(0.0);
+        let minus_zero = NanFreeF32::from(-0.0);
+        assert_eq!(plus_zero, minus_zero);
+        assert_eq!(plus_zero.cmp(&minus_zero), std::cmp::Ordering::Equal);
+        assert!(!plus_zero.bitwise_eq(&minus_zero));
+        assert!(plus_zero.bitwise_eq(&plus_zero));
+        assert!(minus_zero.bitwise_eq(&minus_zero));
+        assert!(NanFreeF32::from(1.5).bitwise_eq(&NanFreeF32::from(1.5)));
+        assert!(!NanFreeF32::from(1.5).bitwise_eq(&NanFreeF32::from(2.5)));
+    }
+}
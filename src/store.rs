@@ -1,4 +1,4 @@
-use std::{ops::{Bound, Deref, Index, RangeBounds}, slice::Iter};
+use std::{cmp::Ordering, iter::Sum, ops::{Add, Bound, Deref, Index, RangeBounds, Sub}, slice::Iter, sync::{Arc, Mutex}};
 
 #[derive(Clone, Debug)]
 pub enum StoreEvent<K, T, M> {
@@ -7,21 +7,118 @@ pub enum StoreEvent<K, T, M> {
     ClearedAll,
     BulkAddedRemoved { added: Vec<(K, T)>, removed: Vec<(K, T)>, metadata: M },
     Changed { from_to: Vec<((K, T), (K, T))>, removed: Vec<(K, T)>, metadata: M },
+    // Only the number of removed entries is recorded (see retain_values_drop()).
+    RemovedCount { count: usize, metadata: M },
 }
 
+impl<K, T, M> StoreEvent<K, T, M> {
+    pub fn map_metadata<N, F: FnOnce(M) -> N>(self, f: F) -> StoreEvent<K, T, N> {
+        match self {
+            StoreEvent::Added { added, metadata } => StoreEvent::Added { added, metadata: f(metadata) },
+            StoreEvent::Removed(removed) => StoreEvent::Removed(removed),
+            StoreEvent::ClearedAll => StoreEvent::ClearedAll,
+            StoreEvent::RemovedCount { count, metadata } => StoreEvent::RemovedCount { count, metadata: f(metadata) },
+            StoreEvent::BulkAddedRemoved { added, removed, metadata } =>
+                StoreEvent::BulkAddedRemoved { added, removed, metadata: f(metadata) },
+            StoreEvent::Changed { from_to, removed, metadata } =>
+                StoreEvent::Changed { from_to, removed, metadata: f(metadata) },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GapError {
+    // Keys of the batch are not strictly ascending.
+    NotSorted,
+    // A key of the batch already exists in the store.
+    Collision,
+    // The batch straddles one or more existing keys.
+    NotInGap,
+}
+
+// Result of Store::apply_event_lenient(). For events having multiple entries, Overwritten takes precedence over Missing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyResult {
+    Applied,
+    // Some entries to remove were not found. The rest of the event is still applied.
+    Missing,
+    // Some entries to add replaced existing ones.
+    Overwritten,
+    // The event does not hold enough information to be replayed. The store is unchanged.
+    NotReplayable,
+}
+
+pub type OnChange<K, T, M> = Box<dyn FnMut(&StoreEvent<K, T, M>) + Send>;
+
+pub type KeyCmp<K> = Arc<dyn Fn(&K, &K) -> Ordering + Send + Sync>;
+
+type NaturalSearch<K, T> = fn(&[(K, T)], &K) -> Result<usize, usize>;
+
+// Ordering of keys. Natural holds plain function pointers so that keys need not be 'static.
 #[derive(Clone)]
-pub struct Store<K: Ord + Copy, T: Clone, M> {
+enum KeyOrder<K, T> {
+    // search is a monomorphized binary_search_by_key so that comparisons are inlined.
+    Natural { cmp: fn(&K, &K) -> Ordering, search: NaturalSearch<K, T> },
+    Custom(KeyCmp<K>),
+}
+
+impl<K, T> KeyOrder<K, T> {
+    #[inline]
+    fn cmp(&self, k0: &K, k1: &K) -> Ordering {
+        match self {
+            KeyOrder::Natural { cmp, .. } => cmp(k0, k1),
+            KeyOrder::Custom(cmp) => cmp(k0, k1),
+        }
+    }
+}
+
+fn natural_search<K: Ord + Copy, T>(store: &[(K, T)], key: &K) -> Result<usize, usize> {
+    store.binary_search_by_key(key, |&(k, _)| k)
+}
+
+pub struct Store<K: Eq + Copy, T: Clone, M> {
     store: Vec<(K, T)>,
+    order: KeyOrder<K, T>,
     events: Option<Vec<StoreEvent<K, T, M>>>,
+    // Wrapped in Mutex only to keep Store Sync. It is called through &mut self, so it is never locked.
+    on_change: Option<Mutex<OnChange<K, T, M>>>,
+    tags: Vec<String>,
+    // Tag stack at the time each held event was fired. Parallel to events. None unless enable_tags() is called.
+    event_tags: Option<Vec<Vec<String>>>,
+}
+
+// The on change callback is not cloned.
+impl<K: Eq + Copy, T: Clone, M: Clone> Clone for Store<K, T, M> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            order: self.order.clone(),
+            events: self.events.clone(),
+            on_change: None,
+            tags: self.tags.clone(),
+            event_tags: self.event_tags.clone(),
+        }
+    }
+}
+
+// Read-only view of entries sorted by key.
+pub trait StoreView<K, T> {
+    fn entries(&self) -> &[(K, T)];
 }
 
-impl<K: Ord + Copy, T: Clone, M> AsRef<Vec<(K, T)>> for Store<K, T, M> {
+impl<K: Eq + Copy, T: Clone, M> StoreView<K, T> for Store<K, T, M> {
+    fn entries(&self) -> &[(K, T)] {
+        &self.store
+    }
+}
+
+impl<K: Eq + Copy, T: Clone, M> AsRef<Vec<(K, T)>> for Store<K, T, M> {
     fn as_ref(&self) -> &Vec<(K, T)> {
         self.store.as_ref()
     }
 }
 
-impl<K: Ord + Copy, T: Clone, M> Deref for Store<K, T, M> {
+impl<K: Eq + Copy, T: Clone, M> Deref for Store<K, T, M> {
     type Target = [(K, T)];
     
     fn deref(&self) -> &Self::Target {
@@ -31,21 +128,38 @@ impl<K: Ord + Copy, T: Clone, M> Deref for Store<K, T, M> {
 
 impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
     pub fn new(hold_events: bool) -> Self {
-        Self { 
-            store: vec![],
-            events: if hold_events { Some(vec![]) } else { None },
-        }
+        Self::with_capacity(0, hold_events)
     }
 
     pub fn with_capacity(capacity: usize, hold_events: bool) -> Self {
+        Self::with_order(capacity, KeyOrder::Natural { cmp: K::cmp, search: natural_search::<K, T> }, hold_events)
+    }
+}
+
+impl<K, T, M> Store<K, T, M> where K: Eq + Copy, T: Clone {
+    // Store whose keys are ordered by the comparator instead of Ord.
+    pub fn new_with_cmp(cmp: impl Fn(&K, &K) -> Ordering + Send + Sync + 'static, hold_events: bool) -> Self {
+        Self::with_order(0, KeyOrder::Custom(Arc::new(cmp)), hold_events)
+    }
+
+    fn with_order(capacity: usize, order: KeyOrder<K, T>, hold_events: bool) -> Self {
         Self { 
             store: Vec::with_capacity(capacity),
+            order,
             events: if hold_events { Some(vec![]) } else { None },
+            on_change: None,
+            tags: vec![],
+            event_tags: None,
         }
     }
 
+    #[inline]
+    fn compare(&self, k0: &K, k1: &K) -> Ordering {
+        self.order.cmp(k0, k1)
+    }
+
     pub fn index(&self, key: K) -> Result<usize, usize> {
-        self.store.binary_search_by_key(&key, |&(k, _)| k)
+        self.find(&key)
     }
 
     #[inline]
@@ -53,24 +167,82 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         self.store.iter().last()
     }
 
+    #[inline]
+    pub fn peek_first(&self) -> Option<&(K, T)> {
+        self.head_entry_option()
+    }
+
+    // Mutable reference to the value of the first entry. Does not fire events.
+    #[inline]
+    pub fn first_mut(&mut self) -> Option<&mut T> {
+        self.store.first_mut().map(|(_, v)| v)
+    }
+
+    // Mutable reference to the value of the last entry. Does not fire events.
+    #[inline]
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        self.store.last_mut().map(|(_, v)| v)
+    }
+
+    // Register a callback invoked after every mutation. Only one callback can be registered. Pass None to clear.
+    pub fn set_on_change(&mut self, cb: Option<OnChange<K, T, M>>) {
+        self.on_change = cb.map(Mutex::new);
+    }
+
+    #[inline]
+    fn is_observed(&self) -> bool {
+        self.events.is_some() || self.on_change.is_some()
+    }
+
     fn fire_event<F>(&mut self, f: F) where F: FnOnce() -> StoreEvent<K, T, M> {
+        if !self.is_observed() {
+            return;
+        }
+
+        let event = f();
+        if let Some(cb) = self.on_change.as_mut().and_then(|cb| cb.get_mut().ok()) {
+            cb(&event);
+        }
         if let Some(events) = self.events.as_mut() {
-            events.push(f());
+            events.push(event);
+            if let Some(event_tags) = self.event_tags.as_mut() {
+                event_tags.push(self.tags.clone());
+            }
         }
     }
 
+    // Start recording the tag stack with each held event. Events already held get empty stacks.
+    // Tags are available only through event_tags(). The on change callback and into_events() do not receive them.
+    pub fn enable_tags(&mut self) {
+        if self.event_tags.is_none() {
+            let len = self.events.as_ref().map_or(0, |events| events.len());
+            self.event_tags = Some(vec![vec![]; len]);
+        }
+    }
+
+    // Push before nested operations and pop after them. Recorded only if enable_tags() is called.
+    pub fn push_tag<S: Into<String>>(&mut self, tag: S) {
+        self.tags.push(tag.into());
+    }
+
+    pub fn pop_tag(&mut self) -> Option<String> {
+        self.tags.pop()
+    }
+
+    // Fires Changed if the key already exists, Added otherwise.
     pub fn add(&mut self, key: K, value: T, metadata: M) -> Option<T> {
-        let mut removed: Option<T> = None;
-        if let Some(r) = self.add_internal(key, value.clone()) {
-            removed = Some(r.clone());
-            self.fire_event(|| StoreEvent::Removed(r));
+        let removed = self.add_internal(key, value.clone());
+        match removed.as_ref() {
+            Some(old) => self.fire_event(|| StoreEvent::Changed {
+                from_to: vec![((key, old.clone()), (key, value))], removed: vec![], metadata
+            }),
+            None => self.fire_event(|| StoreEvent::Added { added: value, metadata }),
         }
-        self.fire_event(|| StoreEvent::Added { added: value, metadata });
         removed
     }
 
     fn add_internal(&mut self, key: K, value: T) -> Option<T> {
-        match self.store.binary_search_by_key(&key, |&(k, _)| k) {
+        match self.find(&key) {
             Ok(i) => {
                 let old = self.store[i].clone();
                 self.store[i] = (key, value);
@@ -92,7 +264,7 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
     }
 
     fn remove_internal(&mut self, key: &K) -> Option<(K, T)> {
-        match self.store.binary_search_by_key(key, |&(k, _)| k) {
+        match self.find(key) {
             Ok(i) => {
                 let e = self.store.remove(i);
                 Some(e)
@@ -142,13 +314,84 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
                 },
             };
 
-        if start_bound == end_bound {
+        if end_bound <= start_bound {
             (0, &self.store[0..0])
         } else {
             (start_bound, &self.store[start_bound..end_bound])
         }
     }
 
+    pub fn get_cloned(&self, key: &K) -> Option<T> {
+        self.find(key).ok().map(|i| self.store[i].1.clone())
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<T> where T: Copy {
+        self.find(key).ok().map(|i| self.store[i].1)
+    }
+
+    // Slice containing just the entry at the key (empty if not found).
+    pub fn at(&self, key: K) -> &[(K, T)] {
+        match self.find(&key) {
+            Ok(i) => &self.store[i..i + 1],
+            Err(_) => &self.store[0..0],
+        }
+    }
+
+    // Cloned entries at or before the key and strictly after the key.
+    #[allow(clippy::type_complexity)]
+    pub fn bracket_owned(&self, key: K) -> (Option<(K, T)>, Option<(K, T)>) {
+        let after = match self.find(&key) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        let before = if after == 0 { None } else { Some(self.store[after - 1].clone()) };
+        (before, self.store.get(after).cloned())
+    }
+
+    // Index of the entry whose key is closest to the key. Ties go to the lower index.
+    // The distances to the adjacent keys are computed with Sub, so this overflows if they do not fit in K
+    // (e.g. keys i32::MIN and i32::MAX with key 0), which panics in debug builds.
+    pub fn nearest_index(&self, key: K) -> Option<usize> where K: Sub<Output = K> + Ord {
+        match self.find(&key) {
+            Ok(i) => Some(i),
+            Err(0) => if self.store.is_empty() { None } else { Some(0) },
+            Err(i) if i == self.store.len() => Some(i - 1),
+            Err(i) => {
+                // prev < key < next
+                let (prev, next) = (self.store[i - 1].0, self.store[i].0);
+                if next - key < key - prev { Some(i) } else { Some(i - 1) }
+            }
+        }
+    }
+
+    // Sums of values in the windows [start, start + width) where start begins at the first key and advances by step
+    // while it does not exceed the last key. The last windows may extend beyond the last key.
+    // Panics if step does not advance start in the key order.
+    pub fn window_sums<B>(&self, width: K, step: K) -> Vec<(K, B)> where K: Add<Output = K>, B: for<'a> Sum<&'a T> {
+        let (mut start, last) = match (self.store.first(), self.store.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return vec![],
+        };
+        let mut sums = vec![];
+
+        loop {
+            let (_, entries) = self.range(start..start + width);
+            sums.push((start, entries.iter().map(|(_, v)| v).sum()));
+
+            let next = start + step;
+            if self.compare(&start, &next) != Ordering::Less {
+                panic!("Step should be positive.");
+            }
+            if self.compare(&last, &next) == Ordering::Less {
+                break;
+            }
+            start = next;
+        }
+
+        sums
+    }
+
+    // Does not fire Changed when none of the 'from' keys exist.
     pub fn change(&mut self, from_to: &[(&K, (K, T))], metadata: M) -> Vec<(K, T)> where T: Clone {
         let mut result: Vec<((K, T), (K, T))> = Vec::with_capacity(from_to.len());
 
@@ -162,27 +405,87 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         // Adding 'to's may replace the existing.
         let mut removed = vec![];
         for (_, (k, v)) in result.iter() {
-            if let Some(r) = self.add_internal(k.clone(), v.clone()) {
-                removed.push((k.clone(), r));
+            if let Some(r) = self.add_internal(*k, v.clone()) {
+                removed.push((*k, r));
             }
         }
-        self.fire_event(|| StoreEvent::Changed { from_to: result, removed: removed.clone(), metadata });
+        if !result.is_empty() {
+            self.fire_event(|| StoreEvent::Changed { from_to: result, removed: removed.clone(), metadata });
+        }
         removed
     }
 
+    // Does not fire BulkAddedRemoved when recs is empty.
     pub fn bulk_add(&mut self, recs: Vec<(K, T)>, metadata: M) -> Vec<(K, T)> where K: Clone, T: Clone {
         let mut removed = vec![];
 
         for (k, v) in recs.iter() {
-            if let Some(r) = self.add_internal(k.clone(), v.clone()) {
+            if let Some(r) = self.add_internal(*k, v.clone()) {
                 removed.push((*k, r));
             }
         }
-        self.fire_event(|| StoreEvent::BulkAddedRemoved { added: recs, removed: removed.clone(), metadata });
+        if !recs.is_empty() {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved { added: recs, removed: removed.clone(), metadata });
+        }
 
         removed
     }
 
+    // Insert a batch whose keys all fall in a single gap between existing adjacent keys in O(n).
+    pub fn insert_gap(&mut self, batch: Vec<(K, T)>, metadata: M) -> Result<(), GapError> {
+        if batch.windows(2).any(|w| self.compare(&w[0].0, &w[1].0) != Ordering::Less) {
+            return Err(GapError::NotSorted);
+        }
+        let (first, last) = match (batch.first(), batch.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return Ok(()),
+        };
+
+        let idx = match (self.find(&first), self.find(&last)) {
+            (Err(i0), Err(i1)) if i0 == i1 => i0,
+            (Err(_), Err(_)) => return Err(GapError::NotInGap),
+            _ => return Err(GapError::Collision),
+        };
+
+        let added = if self.is_observed() { Some(batch.clone()) } else { None };
+        self.store.splice(idx..idx, batch);
+
+        if let Some(added) = added {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved { added, removed: vec![], metadata });
+        }
+        Ok(())
+    }
+
+    // Number of entries in the batch whose keys already exist. Use before bulk_add() to preview overwrites.
+    pub fn count_overwrites(&self, recs: &[(K, T)]) -> usize {
+        recs.iter().filter(|(k, _)| self.find(k).is_ok()).count()
+    }
+
+    // Merge entries of the view. If the key already exists, the value becomes resolve(existing, incoming).
+    pub fn extend_from_view<V: StoreView<K, T>>(&mut self, view: &V, metadata: M, resolve: impl Fn(&T, &T) -> T) {
+        let mut added: Vec<(K, T)> = Vec::with_capacity(view.entries().len());
+        let mut removed: Vec<(K, T)> = vec![];
+
+        for (k, v) in view.entries().iter() {
+            match self.find(k) {
+                Ok(i) => {
+                    let new_value = resolve(&self.store[i].1, v);
+                    let old = std::mem::replace(&mut self.store[i].1, new_value.clone());
+                    removed.push((*k, old));
+                    added.push((*k, new_value));
+                }
+                Err(i) => {
+                    self.store.insert(i, (*k, v.clone()));
+                    added.push((*k, v.clone()));
+                }
+            }
+        }
+        if !added.is_empty() {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved { added, removed, metadata });
+        }
+    }
+
+    // Does not fire BulkAddedRemoved when none of the keys exist.
     pub fn bulk_remove(&mut self, recs: &[K], metadata: M) -> Vec<(K, T)> {
         let mut removed: Vec<(K, T)> = Vec::with_capacity(recs.len());
 
@@ -191,7 +494,29 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
                 removed.push(r);
             }
         }
-        self.fire_event(|| StoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        if !removed.is_empty() {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        }
+
+        removed
+    }
+
+    // Same as bulk_remove() but also returns the index of each entry just before its removal.
+    // Indices are as of each removal, so they are affected by the preceding removals.
+    pub fn bulk_remove_positions(&mut self, recs: &[K], metadata: M) -> Vec<(usize, K, T)> {
+        let mut removed: Vec<(usize, K, T)> = Vec::with_capacity(recs.len());
+
+        for k in recs.iter() {
+            if let Ok(i) = self.find(k) {
+                let (k, v) = self.store.remove(i);
+                removed.push((i, k, v));
+            }
+        }
+        if !removed.is_empty() {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved {
+                added: vec![], removed: removed.iter().map(|(_, k, v)| (*k, v.clone())).collect(), metadata
+            });
+        }
 
         removed
     }
@@ -201,6 +526,11 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         self.store.iter()
     }
 
+    // Owned copy of entries sorted by key.
+    pub fn to_sorted_vec(&self) -> Vec<(K, T)> {
+        self.store.clone()
+    }
+
     pub fn pop_first(&mut self) -> Option<(K, T)> where K: Clone {
         if self.store.is_empty() {
             return None;
@@ -212,6 +542,24 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         Some((k, v))
     }
 
+    pub fn pop_last(&mut self) -> Option<(K, T)> {
+        let (k, v) = self.store.pop()?;
+        self.fire_event(|| StoreEvent::Removed(v.clone()));
+
+        Some((k, v))
+    }
+
+    // Keep the first len entries and remove the rest. Does nothing if the store is not longer than len.
+    pub fn truncate(&mut self, len: usize, metadata: M) -> Vec<(K, T)> {
+        if self.store.len() <= len {
+            return vec![];
+        }
+
+        let removed = self.store.split_off(len);
+        self.fire_event(|| StoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        removed
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.store.len()
@@ -222,23 +570,165 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         self.fire_event(|| StoreEvent::ClearedAll);
     }
 
+    // Same as clear() but releases the allocation. Call shrink_events_to_fit() to release the events as well.
+    pub fn clear_and_shrink(&mut self) {
+        self.store.clear();
+        self.store.shrink_to_fit();
+        self.fire_event(|| StoreEvent::ClearedAll);
+    }
+
     pub fn clear_events(&mut self) {
         if let Some(events) = self.events.as_mut() {
             events.clear();
         }
+        if let Some(event_tags) = self.event_tags.as_mut() {
+            event_tags.clear();
+        }
+    }
+
+    pub fn shrink_events_to_fit(&mut self) {
+        if let Some(events) = self.events.as_mut() {
+            events.shrink_to_fit();
+        }
+        if let Some(event_tags) = self.event_tags.as_mut() {
+            event_tags.shrink_to_fit();
+        }
     }
 
     #[inline]
     pub fn find(&self, key: &K) -> Result<usize, usize> {
-        self.store.binary_search_by_key(key, |&(k, _)| k)
+        match &self.order {
+            KeyOrder::Natural { search, .. } => search(&self.store, key),
+            KeyOrder::Custom(cmp) => self.store.binary_search_by(|(k, _)| cmp(k, key)),
+        }
+    }
+
+    // Restore the invariant (sorted by key without duplicates). Values of duplicated keys are merged by on_dup(earlier, later).
+    // Returns the number of merged duplicates. Does not fire events.
+    pub fn sort_and_dedup(&mut self, on_dup: impl Fn(&T, &T) -> T) -> usize {
+        let len = self.store.len();
+        let order = self.order.clone();
+        self.store.sort_by(|(k0, _), (k1, _)| order.cmp(k0, k1));
+        self.store.dedup_by(|later, earlier| {
+            if order.cmp(&later.0, &earlier.0) == Ordering::Equal {
+                earlier.1 = on_dup(&earlier.1, &later.1);
+                true
+            } else {
+                false
+            }
+        });
+        len - self.store.len()
+    }
+
+    // Binary search by the projection of values. The store must be sorted by the projection as well.
+    pub fn search_by_value_key<B: Ord, F: Fn(&T) -> B>(&self, target: &B, f: F) -> Result<usize, usize> {
+        self.store.binary_search_by(|(_, v)| f(v).cmp(target))
+    }
+
+    // Replay an event tolerating removal of absent keys and addition of existing keys (last write wins).
+    // Since Added and Removed do not hold keys, key_of should return the key of a value.
+    // RemovedCount cannot be replayed since it does not hold the removed entries. It results in NotReplayable.
+    // Removed entries whose key is also added were overwritten within the original operation (e.g. duplicated keys
+    // in bulk_add()). They are expected to be overwritten by the replay as well instead of being removed up front.
+    // Does not fire events.
+    pub fn apply_event_lenient<F>(&mut self, event: &StoreEvent<K, T, M>, key_of: F) -> ApplyResult where F: Fn(&T) -> K {
+        let mut missing = false;
+        let mut overwritten = false;
+
+        match event {
+            StoreEvent::Added { added, .. } => {
+                overwritten = self.add_internal(key_of(added), added.clone()).is_some();
+            }
+            StoreEvent::Removed(removed) => {
+                missing = self.remove_internal(&key_of(removed)).is_none();
+            }
+            StoreEvent::ClearedAll => {
+                self.store.clear();
+            }
+            StoreEvent::RemovedCount { .. } => {
+                return ApplyResult::NotReplayable;
+            }
+            StoreEvent::BulkAddedRemoved { added, removed, .. } => {
+                (missing, overwritten) = self.replay_added_removed(&added.iter().collect::<Vec<_>>(), removed);
+            }
+            StoreEvent::Changed { from_to, removed, .. } => {
+                for ((k, _), _) in from_to.iter() {
+                    missing |= self.remove_internal(k).is_none();
+                }
+                let added: Vec<&(K, T)> = from_to.iter().map(|(_, to)| to).collect();
+                let (m, o) = self.replay_added_removed(&added, removed);
+                missing |= m;
+                overwritten = o;
+            }
+        }
+
+        if overwritten {
+            ApplyResult::Overwritten
+        } else if missing {
+            ApplyResult::Missing
+        } else {
+            ApplyResult::Applied
+        }
+    }
+
+    // Returns (missing, overwritten) of apply_event_lenient().
+    fn replay_added_removed(&mut self, added: &[&(K, T)], removed: &[(K, T)]) -> (bool, bool) {
+        let mut added_keys: Vec<K> = added.iter().map(|(k, _)| *k).collect();
+        added_keys.sort_by(|k0, k1| self.compare(k0, k1));
+
+        let mut missing = false;
+        let mut expected_overwrites: Vec<K> = vec![];
+        for (k, _) in removed.iter() {
+            if added_keys.binary_search_by(|a| self.compare(a, k)).is_ok() {
+                expected_overwrites.push(*k);
+            } else {
+                missing |= self.remove_internal(k).is_none();
+            }
+        }
+        expected_overwrites.sort_by(|k0, k1| self.compare(k0, k1));
+
+        let mut overwritten = false;
+        for (k, v) in added.iter() {
+            if self.add_internal(*k, v.clone()).is_some() {
+                match expected_overwrites.binary_search_by(|e| self.compare(e, k)) {
+                    Ok(i) => { expected_overwrites.remove(i); }
+                    Err(_) => overwritten = true,
+                }
+            }
+        }
+
+        (missing || !expected_overwrites.is_empty(), overwritten)
+    }
+
+    // Convert the metadata of held events. The data is unchanged. The on change callback is not carried over.
+    pub fn map_event_metadata<N, F: Fn(M) -> N>(self, f: F) -> Store<K, T, N> {
+        Store {
+            store: self.store,
+            order: self.order,
+            events: self.events.map(|events| events.into_iter().map(|e| e.map_metadata(&f)).collect()),
+            on_change: None,
+            tags: self.tags,
+            event_tags: self.event_tags,
+        }
     }
 
     pub fn events(&self) -> &Vec<StoreEvent<K, T, M>> {
         self.events.as_ref().expect("Event hold option is disabled. Call new(true).")
     }
 
+    // Consume the store returning held events, or None if the event hold option is disabled.
+    pub fn into_events(self) -> Option<Vec<StoreEvent<K, T, M>>> {
+        self.events
+    }
+
+    // Tag stacks (outermost first) of each event returned by events(), in the same order.
+    pub fn event_tags(&self) -> &Vec<Vec<String>> {
+        self.events.as_ref().expect("Event hold option is disabled. Call new(true).");
+        self.event_tags.as_ref().expect("Tag recording is disabled. Call enable_tags().")
+    }
+
     pub fn update_at_idx(&mut self, idx: usize, new_value: T, metadata: M) {
-        if self.events.is_none() {
+        if !self.is_observed() {
             let e = &self.store[idx];
             self.store[idx] = (e.0, new_value);
         } else {
@@ -251,7 +741,7 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
     }
 
     pub fn replace(&mut self, k: &K, metadata: M, f: impl FnOnce(Option<&T>) -> T) {
-        if self.events.is_none() {
+        if !self.is_observed() {
             match self.find(k) {
                 Ok(idx) => {
                     let current = &self.store[idx];
@@ -285,7 +775,7 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
     }
 
     pub fn replace_mut(&mut self, k: &K, metadata: M, f: impl FnOnce(Option<&mut T>) -> Option<T>) {
-        if self.events.is_none() {
+        if !self.is_observed() {
             match self.find(k) {
                 Ok(idx) => {
                     let current = &mut self.store[idx];
@@ -347,13 +837,14 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
         self.store.is_empty()
     }
 
-    pub fn finder(&self) -> Finder<K, T, M> {
+    pub fn finder(&self) -> Finder<'_, K, T, M> {
         Finder {
             store: self,
             locator: None,
         }
     }
 
+    // Does not fire BulkAddedRemoved when nothing is removed.
     pub fn retain_values<F>(&mut self, metadata: M, f: F) -> Vec<(K, T)>
       where F: Fn(&T) -> bool, K: Clone, T: Clone
     {
@@ -361,24 +852,38 @@ impl<K, T, M> Store<K, T, M> where K: Ord + Copy, T: Clone {
 
         self.store.retain(|(k, v)| {
             if !f(v) {
-                removed.push((k.clone(), v.clone()));
+                removed.push((*k, v.clone()));
                 false
             } else {
                 true
             }
         });
 
-        self.fire_event(|| StoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        if !removed.is_empty() {
+            self.fire_event(|| StoreEvent::BulkAddedRemoved { added: vec![], removed: removed.clone(), metadata });
+        }
         removed
     }
+
+    // Same as retain_values() but does not clone removed entries. Fires RemovedCount instead of BulkAddedRemoved.
+    pub fn retain_values_drop<F>(&mut self, metadata: M, f: F) -> usize where F: Fn(&T) -> bool {
+        let len = self.store.len();
+        self.store.retain(|(_, v)| f(v));
+        let count = len - self.store.len();
+
+        if count != 0 {
+            self.fire_event(|| StoreEvent::RemovedCount { count, metadata });
+        }
+        count
+    }
 }
 
-pub struct Finder<'a, K: Ord + Copy, T: Clone, M> {
+pub struct Finder<'a, K: Eq + Copy, T: Clone, M> {
     store: &'a Store<K, T, M>,
     locator: Option<usize>,
 }
 
-impl <'a, K: Ord + Copy, T: Clone, M> Finder<'a, K, T, M> {
+impl <'a, K: Eq + Copy, T: Clone, M> Finder<'a, K, T, M> {
     fn find_locator(&mut self, k: K) -> Option<usize> {
         match self.store.index(k) {
             Ok(idx) => {
@@ -404,13 +909,14 @@ impl <'a, K: Ord + Copy, T: Clone, M> Finder<'a, K, T, M> {
             Some(locator) =>
                 if locator == len - 1 {
                     let t = &self.store[locator];
-                    if t.0 <= k {
+                    if self.store.compare(&t.0, &k) != Ordering::Greater {
                         Some(t)
                     } else {
                         self.find_locator(k).map(|l| &self.store[l])
                     }
                 } else {
-                    if self.store[locator].0 <= k && k < self.store[locator + 1].0 {
+                    if self.store.compare(&self.store[locator].0, &k) != Ordering::Greater
+                        && self.store.compare(&k, &self.store[locator + 1].0) == Ordering::Less {
                         Some(&self.store[locator])
                     } else {
                         self.find_locator(k).map(|l| &self.store[l])
@@ -421,7 +927,7 @@ impl <'a, K: Ord + Copy, T: Clone, M> Finder<'a, K, T, M> {
     }
 }
 
-impl<K, T, M> Index<usize> for Store<K, T, M> where K: Ord + Copy, T: Clone {
+impl<K, T, M> Index<usize> for Store<K, T, M> where K: Eq + Copy, T: Clone {
     type Output = (K, T);
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -431,8 +937,9 @@ impl<K, T, M> Index<usize> for Store<K, T, M> where K: Ord + Copy, T: Clone {
 
 #[cfg(test)]
 mod tests {
-    use crate::store::StoreEvent;
-    use super::Store;
+    use std::{cell::{Cell, RefCell}, ops::Bound, rc::Rc, sync::{Arc, Mutex}};
+    use crate::store::{ApplyResult, GapError, StoreEvent};
+    use super::{Store, StoreView};
 
     #[test]
     fn finder_empty() {
@@ -474,7 +981,7 @@ mod tests {
         let mut store = Store::new(false);
         store.add(10, "10", "");
 
-        let (idx, mut itr) = store.range(0..=i32::MAX);
+        let (idx, itr) = store.range(0..=i32::MAX);
         assert_eq!(idx, 0);
         assert_eq!(itr.len(), 1);
         assert_eq!(itr[0], (10, "10"));
@@ -494,7 +1001,7 @@ mod tests {
         assert_eq!(store[0].1, "102".to_owned());
 
         store.replace(&20, "foo", |v| {
-            assert_eq!(v.is_none(), true);
+            assert!(v.is_none());
             "20".to_owned()
         });
         assert_eq!(store.len(), 2);
@@ -535,7 +1042,7 @@ mod tests {
         store.clear_events();
 
         store.replace(&20, "bar", |v| {
-            assert_eq!(v.is_none(), true);
+            assert!(v.is_none());
             "20".to_owned()
         });
         assert_eq!(store.len(), 2);
@@ -606,7 +1113,7 @@ mod tests {
         store.clear_events();
 
         store.replace_mut(&10, "meta", |opt| {
-            if let Some(value) = opt {
+            if let Some(_value) = opt {
                 Some(vec![2, 3, 4])
             } else {
                 panic!("Unexpected state.");
@@ -657,7 +1164,7 @@ mod tests {
         assert_eq!(store[0], (10, vec![100, 2, 3]));
 
         store.replace_mut(&10, (), |opt| {
-            if let Some(value) = opt {
+            if let Some(_value) = opt {
                 Some(vec![2, 3, 4])
             } else {
                 panic!("Unexpected state.");
@@ -700,7 +1207,7 @@ mod tests {
         store.clear_events();
         let removed = store.retain_values(123, |v| v.len() == 1);
         assert_eq!(store.len(), 2);
-        assert_eq!(store.get(0).unwrap(), &(0, "0"));
+        assert_eq!(store.first().unwrap(), &(0, "0"));
         assert_eq!(store.get(1).unwrap(), &(3, "3"));
         assert_eq!(removed, vec![(1, "11"), (2, "22")]);
 
@@ -715,4 +1222,716 @@ mod tests {
             _ => panic!("Logic error."),
         }
     }
+
+    #[test]
+    fn on_change() {
+        let fired = Arc::new(Mutex::new(Vec::<StoreEvent<i32, &str, &str>>::new()));
+        let mut store: Store<i32, &str, &str> = Store::new(false);
+        let f = fired.clone();
+        store.set_on_change(Some(Box::new(move |e| f.lock().unwrap().push(e.clone()))));
+
+        store.add(10, "10", "add");
+        store.update_at_idx(0, "11", "update");
+        store.remove(&10);
+
+        {
+            let fired = fired.lock().unwrap();
+            assert_eq!(fired.len(), 3);
+            match &fired[0] {
+                StoreEvent::Added { added, metadata } => {
+                    assert_eq!(*added, "10");
+                    assert_eq!(*metadata, "add");
+                }
+                _ => panic!("Unexpected event {:?}", fired),
+            }
+            match &fired[1] {
+                StoreEvent::Changed { from_to, removed, metadata } => {
+                    assert_eq!(from_to, &vec![((10, "10"), (10, "11"))]);
+                    assert_eq!(removed.len(), 0);
+                    assert_eq!(*metadata, "update");
+                }
+                _ => panic!("Unexpected event {:?}", fired),
+            }
+            match &fired[2] {
+                StoreEvent::Removed(removed) => assert_eq!(*removed, "11"),
+                _ => panic!("Unexpected event {:?}", fired),
+            }
+        }
+
+        store.set_on_change(None);
+        store.add(20, "20", "add");
+        assert_eq!(fired.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn pop_last() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        assert_eq!(store.pop_last(), None);
+        assert_eq!(store.peek_first(), None);
+
+        store.add(10, "10", "");
+        store.add(20, "20", "");
+        assert_eq!(store.peek_first(), Some(&(10, "10")));
+        store.clear_events();
+
+        assert_eq!(store.pop_last(), Some((20, "20")));
+        assert_eq!(store.len(), 1);
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::Removed(removed) => assert_eq!(*removed, "20"),
+            _ => panic!("Unexpected event {:?}", events),
+        }
+
+        assert_eq!(store.pop_last(), Some((10, "10")));
+        assert_eq!(store.pop_last(), None);
+    }
+
+    #[test]
+    fn shrink_events_to_fit() {
+        let mut store: Store<i32, i32, ()> = Store::new(true);
+        for i in 0..100 {
+            store.add(i, i, ());
+        }
+        let capacity = store.events.as_ref().unwrap().capacity();
+        assert!(100 <= capacity);
+
+        store.clear_events();
+        assert_eq!(store.events.as_ref().unwrap().capacity(), capacity);
+        store.shrink_events_to_fit();
+        assert!(store.events.as_ref().unwrap().capacity() < capacity);
+
+        // No-op when events are disabled.
+        let mut store: Store<i32, i32, ()> = Store::new(false);
+        store.shrink_events_to_fit();
+    }
+
+    #[test]
+    fn add_events() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        assert_eq!(store.add(10, "10", "first"), None);
+        assert_eq!(store.add(20, "20", "second"), None);
+
+        let events = store.events();
+        assert_eq!(events.len(), 2);
+        match &events[1] {
+            StoreEvent::Added { added, metadata } => {
+                assert_eq!(*added, "20");
+                assert_eq!(*metadata, "second");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+        store.clear_events();
+
+        assert_eq!(store.add(10, "11", "third"), Some("10"));
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::Changed { from_to, removed, metadata } => {
+                assert_eq!(from_to, &vec![((10, "10"), (10, "11"))]);
+                assert_eq!(removed.len(), 0);
+                assert_eq!(*metadata, "third");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+        assert_eq!(store.len(), 2);
+        assert_eq!(store[0], (10, "11"));
+    }
+
+    #[test]
+    fn insert_gap() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        store.add(10, "10", "");
+        store.add(20, "20", "");
+        store.clear_events();
+
+        assert_eq!(store.insert_gap(vec![(12, "12"), (15, "15"), (18, "18")], "gap"), Ok(()));
+        assert_eq!(store.as_ref(), &vec![(10, "10"), (12, "12"), (15, "15"), (18, "18"), (20, "20")]);
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                assert_eq!(added, &vec![(12, "12"), (15, "15"), (18, "18")]);
+                assert_eq!(removed.len(), 0);
+                assert_eq!(*metadata, "gap");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+        store.clear_events();
+
+        assert_eq!(store.insert_gap(vec![(1, "1"), (2, "2")], "head"), Ok(()));
+        assert_eq!(store.insert_gap(vec![(30, "30")], "tail"), Ok(()));
+        assert_eq!(store.len(), 8);
+        assert_eq!(store[0], (1, "1"));
+        assert_eq!(store[7], (30, "30"));
+    }
+
+    #[test]
+    fn insert_gap_error() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        store.add(10, "10", "");
+        store.add(20, "20", "");
+        store.clear_events();
+
+        assert_eq!(store.insert_gap(vec![(15, "15"), (20, "20")], ""), Err(GapError::Collision));
+        assert_eq!(store.insert_gap(vec![(15, "15"), (25, "25")], ""), Err(GapError::NotInGap));
+        assert_eq!(store.insert_gap(vec![(15, "15"), (12, "12")], ""), Err(GapError::NotSorted));
+        assert_eq!(store.insert_gap(vec![(15, "15"), (15, "15")], ""), Err(GapError::NotSorted));
+        assert_eq!(store.as_ref(), &vec![(10, "10"), (20, "20")]);
+        assert_eq!(store.events().len(), 0);
+    }
+
+    #[test]
+    fn search_by_value_key() {
+        let mut store: Store<i32, (u8, &str), ()> = Store::new(false);
+        store.add(10, (1, "a"), ());
+        store.add(20, (3, "b"), ());
+        store.add(30, (5, "c"), ());
+
+        assert_eq!(store.search_by_value_key(&3, |v| v.0), Ok(1));
+        assert_eq!(store.search_by_value_key(&5, |v| v.0), Ok(2));
+        assert_eq!(store.search_by_value_key(&0, |v| v.0), Err(0));
+        assert_eq!(store.search_by_value_key(&4, |v| v.0), Err(2));
+        assert_eq!(store.search_by_value_key(&6, |v| v.0), Err(3));
+    }
+
+    #[test]
+    fn clear_and_shrink() {
+        let mut store: Store<i32, i32, ()> = Store::with_capacity(100, true);
+        for i in 0..100 {
+            store.add(i, i, ());
+        }
+        let capacity = store.store.capacity();
+
+        store.clear();
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.store.capacity(), capacity);
+
+        for i in 0..100 {
+            store.add(i, i, ());
+        }
+        store.clear_events();
+        store.clear_and_shrink();
+        assert_eq!(store.len(), 0);
+        assert!(store.store.capacity() < capacity);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::ClearedAll => {}
+            _ => panic!("Unexpected event {:?}", events),
+        }
+    }
+
+    #[test]
+    fn at() {
+        let mut store: Store<i32, &str, ()> = Store::new(false);
+        assert_eq!(store.at(10), &[]);
+
+        store.add(10, "10", ());
+        store.add(20, "20", ());
+        assert_eq!(store.at(10), &[(10, "10")]);
+        assert_eq!(store.at(20), &[(20, "20")]);
+        assert_eq!(store.at(15), &[]);
+        assert_eq!(store.at(30), &[]);
+    }
+
+    #[test]
+    fn apply_event_lenient() {
+        let key_of = |v: &(i32, &str)| v.0;
+        let mut store: Store<i32, (i32, &str), &str> = Store::new(true);
+        store.add(10, (10, "a"), "");
+        store.add(20, (20, "b"), "");
+        store.clear_events();
+
+        // Removing an absent key is skipped.
+        let result = store.apply_event_lenient(&StoreEvent::Removed((30, "c")), key_of);
+        assert_eq!(result, ApplyResult::Missing);
+        assert_eq!(store.as_ref(), &vec![(10, (10, "a")), (20, (20, "b"))]);
+
+        // Adding an existing key overwrites.
+        let result = store.apply_event_lenient(&StoreEvent::Added { added: (10, "x"), metadata: "" }, key_of);
+        assert_eq!(result, ApplyResult::Overwritten);
+        assert_eq!(store.as_ref(), &vec![(10, (10, "x")), (20, (20, "b"))]);
+
+        let result = store.apply_event_lenient(&StoreEvent::Removed((20, "b")), key_of);
+        assert_eq!(result, ApplyResult::Applied);
+        let result = store.apply_event_lenient(&StoreEvent::Added { added: (30, "c"), metadata: "" }, key_of);
+        assert_eq!(result, ApplyResult::Applied);
+        assert_eq!(store.as_ref(), &vec![(10, (10, "x")), (30, (30, "c"))]);
+
+        let result = store.apply_event_lenient(&StoreEvent::BulkAddedRemoved {
+            added: vec![(40, (40, "d"))], removed: vec![(10, (10, "x")), (50, (50, "e"))], metadata: ""
+        }, key_of);
+        assert_eq!(result, ApplyResult::Missing);
+        assert_eq!(store.as_ref(), &vec![(30, (30, "c")), (40, (40, "d"))]);
+
+        let result = store.apply_event_lenient(&StoreEvent::Changed {
+            from_to: vec![((30, (30, "c")), (40, (40, "f")))], removed: vec![], metadata: ""
+        }, key_of);
+        assert_eq!(result, ApplyResult::Overwritten);
+        assert_eq!(store.as_ref(), &vec![(40, (40, "f"))]);
+
+        // Replay does not fire events.
+        assert_eq!(store.events().len(), 0);
+    }
+
+    #[test]
+    fn apply_event_lenient_replays_events() {
+        let key_of = |v: &(i32, &str)| v.0;
+        let mut src: Store<i32, (i32, &str), &str> = Store::new(true);
+        src.add(10, (10, "a"), "");
+        src.add(20, (20, "b"), "");
+        src.add(10, (10, "c"), "");
+        src.bulk_add(vec![(30, (30, "d")), (20, (20, "e"))], "");
+        src.change(&[(&30, (40, (40, "d")))], "");
+        src.remove(&10);
+        // Duplicated keys within a batch.
+        src.bulk_add(vec![(5, (5, "f")), (5, (5, "g")), (20, (20, "h"))], "");
+        src.change(&[(&5, (60, (60, "i"))), (&20, (60, (60, "j")))], "");
+        let mut view: Store<i32, (i32, &str), &str> = Store::new(false);
+        view.add(40, (40, "k"), "");
+        src.extend_from_view(&view, "", |_, v| *v);
+
+        let mut dest: Store<i32, (i32, &str), &str> = Store::new(false);
+        for e in src.events().iter() {
+            assert_eq!(dest.apply_event_lenient(e, key_of), ApplyResult::Applied);
+        }
+        assert_eq!(dest.as_ref(), src.as_ref());
+    }
+
+    #[test]
+    fn nearest_index() {
+        let mut store: Store<i32, &str, ()> = Store::new(false);
+        assert_eq!(store.nearest_index(10), None);
+
+        store.add(10, "10", ());
+        assert_eq!(store.nearest_index(0), Some(0));
+        assert_eq!(store.nearest_index(20), Some(0));
+
+        store.add(20, "20", ());
+        store.add(30, "30", ());
+        assert_eq!(store.nearest_index(20), Some(1));
+        assert_eq!(store.nearest_index(13), Some(0));
+        assert_eq!(store.nearest_index(17), Some(1));
+        assert_eq!(store.nearest_index(15), Some(0));
+        assert_eq!(store.nearest_index(25), Some(1));
+        assert_eq!(store.nearest_index(-100), Some(0));
+        assert_eq!(store.nearest_index(100), Some(2));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn nearest_index_should_panic_on_overflow() {
+        let mut store: Store<i32, &str, ()> = Store::new(false);
+        store.add(i32::MIN, "min", ());
+        store.add(i32::MAX, "max", ());
+        let _ = store.nearest_index(0);
+    }
+
+    #[test]
+    fn sort_and_dedup() {
+        let mut store: Store<i32, String, ()> = Store::new(false);
+        assert_eq!(store.sort_and_dedup(|a, b| format!("{}{}", a, b)), 0);
+
+        store.store = vec![
+            (30, "c".to_owned()), (10, "a".to_owned()), (20, "b".to_owned()),
+            (10, "d".to_owned()), (30, "e".to_owned()), (10, "f".to_owned()),
+        ];
+        assert_eq!(store.sort_and_dedup(|a, b| format!("{}{}", a, b)), 3);
+        assert_eq!(store.as_ref(), &vec![(10, "adf".to_owned()), (20, "b".to_owned()), (30, "ce".to_owned())]);
+        assert_eq!(store.find(&20), Ok(1));
+
+        assert_eq!(store.sort_and_dedup(|a, b| format!("{}{}", a, b)), 0);
+    }
+
+    #[test]
+    fn to_sorted_vec() {
+        let mut store: Store<i32, &str, ()> = Store::new(false);
+        assert_eq!(store.to_sorted_vec(), vec![]);
+
+        store.add(30, "30", ());
+        store.add(10, "10", ());
+        store.add(20, "20", ());
+        let vec = store.to_sorted_vec();
+        assert_eq!(vec, vec![(10, "10"), (20, "20"), (30, "30")]);
+        assert!(vec.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn tags() {
+        let mut store: Store<i32, &str, ()> = Store::new(true);
+        store.push_tag("ignored");
+        store.add(-10, "-10", ());
+        assert_eq!(store.pop_tag(), Some("ignored".to_owned()));
+        store.enable_tags();
+        assert_eq!(store.event_tags(), &vec![Vec::<String>::new()]);
+        store.clear_events();
+
+        store.add(0, "0", ());
+
+        store.push_tag("user-edit");
+        store.add(10, "10", ());
+        store.push_tag("macro-expansion");
+        store.add(20, "20", ());
+        store.remove(&0);
+        assert_eq!(store.pop_tag(), Some("macro-expansion".to_owned()));
+        store.add(30, "30", ());
+        assert_eq!(store.pop_tag(), Some("user-edit".to_owned()));
+        assert_eq!(store.pop_tag(), None);
+        store.add(40, "40", ());
+
+        assert_eq!(store.events().len(), 6);
+        let tags = store.event_tags();
+        assert_eq!(tags.len(), 6);
+        assert!(tags[0].is_empty());
+        assert_eq!(tags[1], vec!["user-edit"]);
+        assert_eq!(tags[2], vec!["user-edit", "macro-expansion"]);
+        assert_eq!(tags[3], vec!["user-edit", "macro-expansion"]);
+        assert_eq!(tags[4], vec!["user-edit"]);
+        assert!(tags[5].is_empty());
+
+        store.clear_events();
+        assert!(store.event_tags().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn event_tags_should_require_enable_tags() {
+        let mut store: Store<i32, &str, ()> = Store::new(true);
+        store.push_tag("user-edit");
+        store.add(0, "0", ());
+        let _ = store.event_tags();
+    }
+
+    #[test]
+    fn bulk_remove_positions() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        for i in 0..5 {
+            store.add(i * 10, "", "");
+        }
+        store.clear_events();
+
+        let removed = store.bulk_remove_positions(&[10, 40, 15, 0], "meta");
+        assert_eq!(removed, vec![(1, 10, ""), (3, 40, ""), (0, 0, "")]);
+        assert_eq!(store.as_ref(), &vec![(20, ""), (30, "")]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                assert_eq!(added.len(), 0);
+                assert_eq!(removed, &vec![(10, ""), (40, ""), (0, "")]);
+                assert_eq!(*metadata, "meta");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+    }
+
+    #[test]
+    fn bracket_owned() {
+        let mut store: Store<i32, String, ()> = Store::new(false);
+        assert_eq!(store.bracket_owned(10), (None, None));
+
+        store.add(10, "10".to_owned(), ());
+        store.add(20, "20".to_owned(), ());
+        assert_eq!(store.bracket_owned(5), (None, Some((10, "10".to_owned()))));
+        assert_eq!(store.bracket_owned(10), (Some((10, "10".to_owned())), Some((20, "20".to_owned()))));
+        assert_eq!(store.bracket_owned(15), (Some((10, "10".to_owned())), Some((20, "20".to_owned()))));
+        assert_eq!(store.bracket_owned(20), (Some((20, "20".to_owned())), None));
+        assert_eq!(store.bracket_owned(25), (Some((20, "20".to_owned())), None));
+
+        let (before, after) = store.bracket_owned(15);
+        drop(store);
+        assert_eq!(before.unwrap().1, "10");
+        assert_eq!(after.unwrap().1, "20");
+    }
+
+    #[test]
+    fn count_overwrites() {
+        let mut store: Store<i32, &str, ()> = Store::new(false);
+        assert_eq!(store.count_overwrites(&[(10, "a")]), 0);
+
+        store.add(10, "10", ());
+        store.add(20, "20", ());
+        store.add(30, "30", ());
+        let batch = vec![(5, "a"), (10, "b"), (25, "c"), (30, "d")];
+        assert_eq!(store.count_overwrites(&batch), 2);
+        assert_eq!(store.count_overwrites(&[]), 0);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.bulk_add(batch, ()).len(), 2);
+    }
+
+    #[test]
+    fn truncate() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        store.add(10, "10", "");
+        store.add(20, "20", "");
+        store.add(30, "30", "");
+        store.clear_events();
+
+        assert_eq!(store.truncate(3, "meta"), vec![]);
+        assert_eq!(store.truncate(5, "meta"), vec![]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.events().len(), 0);
+
+        assert_eq!(store.truncate(1, "meta"), vec![(20, "20"), (30, "30")]);
+        assert_eq!(store.as_ref(), &vec![(10, "10")]);
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                assert_eq!(added.len(), 0);
+                assert_eq!(removed, &vec![(20, "20"), (30, "30")]);
+                assert_eq!(*metadata, "meta");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+    }
+
+    #[test]
+    fn extend_from_view() {
+        let mut view: Store<i32, i32, ()> = Store::new(false);
+        view.add(10, 1, ());
+        view.add(20, 2, ());
+        view.add(40, 4, ());
+        assert_eq!(view.entries(), &[(10, 1), (20, 2), (40, 4)]);
+
+        let mut store: Store<i32, i32, &str> = Store::new(true);
+        store.add(20, 200, "");
+        store.add(30, 300, "");
+        store.clear_events();
+
+        store.extend_from_view(&view, "merge", |existing, incoming| existing + incoming);
+        assert_eq!(store.as_ref(), &vec![(10, 1), (20, 202), (30, 300), (40, 4)]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::BulkAddedRemoved { added, removed, metadata } => {
+                assert_eq!(added, &vec![(10, 1), (20, 202), (40, 4)]);
+                assert_eq!(removed, &vec![(20, 200)]);
+                assert_eq!(*metadata, "merge");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+    }
+
+    #[test]
+    fn map_event_metadata() {
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        store.add(10, "10", "add");
+        store.bulk_remove(&[10], "remove");
+        store.add(20, "20", "add");
+        store.clear();
+
+        let store: Store<i32, &str, String> = store.map_event_metadata(|m| format!("mapped {}", m));
+        assert_eq!(store.len(), 0);
+        let events = store.events();
+        assert_eq!(events.len(), 4);
+        match &events[0] {
+            StoreEvent::Added { added, metadata } => {
+                assert_eq!(*added, "10");
+                assert_eq!(metadata, "mapped add");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+        match &events[1] {
+            StoreEvent::BulkAddedRemoved { removed, metadata, .. } => {
+                assert_eq!(removed, &vec![(10, "10")]);
+                assert_eq!(metadata, "mapped remove");
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+        match &events[3] {
+            StoreEvent::ClearedAll => {}
+            _ => panic!("Unexpected event {:?}", events),
+        }
+    }
+
+    #[test]
+    fn retain_drop() {
+        let mut store: Store<i32, &str, i32> = Store::new(true);
+        store.add(0, "0", 0);
+        store.add(1, "11", 0);
+        store.add(2, "22", 0);
+        store.add(3, "3", 0);
+
+        store.clear_events();
+        assert_eq!(store.retain_values_drop(123, |v| v.len() == 1), 2);
+        assert_eq!(store.as_ref(), &vec![(0, "0"), (3, "3")]);
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            StoreEvent::RemovedCount { count, metadata } => {
+                assert_eq!(*count, 2);
+                assert_eq!(*metadata, 123);
+            }
+            _ => panic!("Unexpected event {:?}", events),
+        }
+
+        let mut dest: Store<i32, &str, i32> = Store::new(false);
+        dest.add(0, "0", 0);
+        assert_eq!(dest.apply_event_lenient(&events[0], |v| v.len() as i32), ApplyResult::NotReplayable);
+        assert_eq!(dest.as_ref(), &vec![(0, "0")]);
+    }
+
+    #[derive(Debug)]
+    struct CloneCounter(i32, Rc<RefCell<usize>>);
+
+    impl Clone for CloneCounter {
+        fn clone(&self) -> Self {
+            *self.1.borrow_mut() += 1;
+            Self(self.0, self.1.clone())
+        }
+    }
+
+    #[test]
+    fn retain_drop_does_not_clone() {
+        let clones = Rc::new(RefCell::new(0));
+        let mut store: Store<i32, CloneCounter, ()> = Store::new(true);
+        store.store = (0..10).map(|i| (i, CloneCounter(i, clones.clone()))).collect();
+
+        assert_eq!(store.retain_values_drop((), |v| v.0 % 2 == 0), 5);
+        assert_eq!(store.len(), 5);
+        assert_eq!(*clones.borrow(), 0);
+    }
+
+    #[test]
+    fn first_last_mut() {
+        let mut store: Store<i32, String, ()> = Store::new(true);
+        assert_eq!(store.first_mut(), None);
+        assert_eq!(store.last_mut(), None);
+
+        store.add(10, "10".to_owned(), ());
+        store.add(20, "20".to_owned(), ());
+        store.add(30, "30".to_owned(), ());
+        store.clear_events();
+
+        store.first_mut().unwrap().push('a');
+        store.last_mut().unwrap().push('z');
+        assert_eq!(store.as_ref(), &vec![(10, "10a".to_owned()), (20, "20".to_owned()), (30, "30z".to_owned())]);
+        assert_eq!(store.events().len(), 0);
+    }
+
+    #[test]
+    fn into_events() {
+        let store: Store<i32, &str, &str> = Store::new(false);
+        assert!(store.into_events().is_none());
+
+        let mut store: Store<i32, &str, &str> = Store::new(true);
+        store.add(10, "10", "add");
+        store.remove(&10);
+        let expected = format!("{:?}", store.events());
+
+        let events = store.into_events().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(format!("{:?}", events), expected);
+    }
+
+    #[test]
+    fn window_sums() {
+        let mut store: Store<i32, i32, ()> = Store::new(false);
+        assert_eq!(store.window_sums::<i32>(30, 20), vec![]);
+
+        for i in 0..10 {
+            store.add(i * 10, i + 1, ());
+        }
+        assert_eq!(store.window_sums::<i32>(30, 20), vec![(0, 6), (20, 12), (40, 18), (60, 24), (80, 19)]);
+        assert_eq!(store.window_sums::<i32>(10, 10), (0..10).map(|i| (i * 10, i + 1)).collect::<Vec<_>>());
+        assert_eq!(store.window_sums::<i32>(1000, 1000), vec![(0, 55)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn window_sums_should_reject_non_positive_step() {
+        let mut store: Store<i32, i32, ()> = Store::new(false);
+        store.add(0, 1, ());
+        store.add(10, 1, ());
+        let _ = store.window_sums::<i32>(10, 0);
+    }
+
+    #[test]
+    fn retain_without_removal_fires_no_event() {
+        let mut store: Store<i32, &str, i32> = Store::new(true);
+        store.add(0, "0", 0);
+        store.add(1, "11", 0);
+        store.clear_events();
+
+        assert_eq!(store.retain_values(123, |_| true), vec![]);
+        assert_eq!(store.retain_values_drop(123, |_| true), 0);
+        assert_eq!(store.bulk_remove(&[5], 123), vec![]);
+        assert_eq!(store.bulk_add(vec![], 123), vec![]);
+        assert_eq!(store.change(&[(&5, (6, "6"))], 123), vec![]);
+        assert_eq!(store.truncate(2, 123), vec![]);
+        assert_eq!(store.events().len(), 0);
+
+        assert_eq!(store.retain_values(123, |v| v.len() == 1), vec![(1, "11")]);
+        assert_eq!(store.events().len(), 1);
+    }
+
+    #[test]
+    fn get_cloned() {
+        let mut store: Store<i32, String, ()> = Store::new(false);
+        assert_eq!(store.get_cloned(&10), None);
+
+        store.add(10, "10".to_owned(), ());
+        assert_eq!(store.get_cloned(&10), Some("10".to_owned()));
+        assert_eq!(store.get_cloned(&20), None);
+    }
+
+    #[test]
+    fn get_copied() {
+        let mut store: Store<i32, f32, ()> = Store::new(false);
+        assert_eq!(store.get_copied(&10), None);
+
+        store.add(10, 1.5, ());
+        assert_eq!(store.get_copied(&10), Some(1.5));
+        assert_eq!(store.get_copied(&20), None);
+    }
+
+    #[test]
+    fn custom_order() {
+        let mut store: Store<i32, &str, ()> = Store::new_with_cmp(|k0: &i32, k1: &i32| k1.cmp(k0), true);
+        store.add(10, "10", ());
+        store.add(30, "30", ());
+        store.add(20, "20", ());
+        assert_eq!(store.add(20, "20'", ()), Some("20"));
+        assert_eq!(store.as_ref(), &vec![(30, "30"), (20, "20'"), (10, "10")]);
+
+        assert_eq!(store.find(&30), Ok(0));
+        assert_eq!(store.find(&10), Ok(2));
+        assert_eq!(store.find(&25), Err(1));
+        assert_eq!(store.find(&5), Err(3));
+
+        let (idx, slice) = store.range((Bound::Included(25), Bound::Included(10)));
+        assert_eq!(idx, 1);
+        assert_eq!(slice, &[(20, "20'"), (10, "10")]);
+        let (_, slice) = store.range(10..25);
+        assert!(slice.is_empty());
+
+        let mut finder = store.finder();
+        assert_eq!(finder.just_before(35), None);
+        assert_eq!(finder.just_before(25), Some(&(30, "30")));
+        assert_eq!(finder.just_before(5), Some(&(10, "10")));
+
+        assert_eq!(store.remove(&20), Some((20, "20'")));
+        assert_eq!(store.clone().as_ref(), &vec![(30, "30"), (10, "10")]);
+        assert_eq!(store.insert_gap(vec![(25, "25"), (15, "15")], ()), Ok(()));
+        assert_eq!(store.as_ref(), &vec![(30, "30"), (25, "25"), (15, "15"), (10, "10")]);
+    }
+
+    #[test]
+    fn store_is_send_and_sync() {
+        fn is_send_sync<S: Send + Sync>(_: &S) {}
+
+        // The callback need not be Sync.
+        let count = Cell::new(0);
+        let mut store: Store<i32, i32, ()> = Store::new(true);
+        store.set_on_change(Some(Box::new(move |_| count.set(count.get() + 1))));
+        is_send_sync(&store);
+        is_send_sync(&Store::<i32, i32, ()>::new_with_cmp(|k0: &i32, k1: &i32| k1.cmp(k0), true));
+    }
 }